@@ -0,0 +1,362 @@
+//! Abstraction over archive containers so [`crate::extract_files`] can walk zips and tarballs
+//! the same way.
+//!
+//! [`ArchiveSource`] hides whether entries come from a zip central directory or a tar stream
+//! (optionally wrapped in a gzip/xz/zstd/bzip2 decoder) behind a single visitor-style walk.
+use std::fs::File;
+use std::io::{self, Cursor, Read, Seek};
+use std::path::{Path, PathBuf};
+
+use bzip2::read::BzDecoder;
+use flate2::read::GzDecoder;
+use tar::Archive as TarArchive;
+use xz2::read::XzDecoder;
+use zip::read::{ZipArchive, ZipFile};
+use zstd::stream::read::Decoder as ZstdDecoder;
+
+use crate::supported_compression_method;
+
+/// One entry yielded while walking an [`ArchiveSource`].
+pub struct ArchiveEntry<'a> {
+    pub path: PathBuf,
+    pub reader: Box<dyn Read + 'a>,
+}
+
+/// A container that can be walked entry-by-entry regardless of the underlying archive format.
+///
+/// Requires `Send` so archives can be handed off to worker threads, e.g. by [`crate::extract_files`]'s
+/// `--jobs` worker pool.
+pub trait ArchiveSource: Send {
+    /// Visits every entry in the archive, calling `visit` with the entry's path and a reader
+    /// positioned at the start of its contents. Unsupported entries, and encrypted zip entries
+    /// that can't be decrypted, are skipped; an error reading the archive ends the walk with `Err`.
+    fn for_each_entry(&mut self, visit: &mut dyn FnMut(ArchiveEntry)) -> io::Result<()>;
+}
+
+/// The archive formats [`open_archive`] knows how to recognize and read.
+enum ArchiveFormat {
+    Zip,
+    Tar,
+    TarGz,
+    TarXz,
+    TarZst,
+    TarBz2,
+}
+
+/// Determines the archive format of `path` from its file name, or `None` if it isn't one we
+/// support.
+fn archive_format(path: &Path) -> Option<ArchiveFormat> {
+    let name = path.file_name()?.to_str()?;
+    if name.ends_with(".tar.gz") || name.ends_with(".tgz") {
+        Some(ArchiveFormat::TarGz)
+    } else if name.ends_with(".tar.xz") {
+        Some(ArchiveFormat::TarXz)
+    } else if name.ends_with(".tar.zst") {
+        Some(ArchiveFormat::TarZst)
+    } else if name.ends_with(".tar.bz2") {
+        Some(ArchiveFormat::TarBz2)
+    } else if name.ends_with(".tar") {
+        Some(ArchiveFormat::Tar)
+    } else if name.ends_with(".zip") {
+        Some(ArchiveFormat::Zip)
+    } else {
+        None
+    }
+}
+
+/// Checks if `path` names a file in a supported archive format.
+pub fn path_is_archive(path: &Path) -> bool {
+    path.is_file() && archive_format(path).is_some()
+}
+
+/// Checks if `name`'s extension names a supported archive format, without touching the
+/// filesystem. Used to recognize nested archives inside another archive's entries.
+pub fn is_archive_name(name: &Path) -> bool {
+    archive_format(name).is_some()
+}
+
+/// Strips the archive extension (including compound extensions like `.tar.gz`) from a file name.
+pub fn archive_stem(path: &Path) -> Option<String> {
+    let name = path.file_name()?.to_str()?;
+    let suffixes = [
+        ".tar.gz", ".tar.xz", ".tar.zst", ".tar.bz2", ".tgz", ".tar", ".zip",
+    ];
+    for suffix in suffixes {
+        if let Some(stripped) = name.strip_suffix(suffix) {
+            return Some(stripped.to_string());
+        }
+    }
+    None
+}
+
+/// Opens `path` and returns an [`ArchiveSource`] appropriate for its format.
+///
+/// `password` decrypts AES/ZipCrypto-protected zip entries; it's ignored for tar-family formats,
+/// which have no notion of per-entry encryption.
+///
+/// Returns an error if `path` isn't a recognized archive or can't be opened.
+pub fn open_archive(path: &Path, password: Option<&[u8]>) -> io::Result<Box<dyn ArchiveSource>> {
+    let format = archive_format(path)
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "unsupported archive format"))?;
+    let file = File::open(path)?;
+    build_archive_source(format, file, password)
+}
+
+/// Opens a nested archive held entirely in memory.
+///
+/// Entry readers from an enclosing [`ArchiveSource`] generally aren't seekable, so recursive
+/// extraction buffers a nested archive's bytes before reopening them here. `name` is used only
+/// to determine the archive format from its extension.
+///
+/// Always opens without a password, so a password-protected zip nested inside another archive
+/// won't be readable; there's currently no way to propagate `--password`/`--password-file` into
+/// nested archives.
+pub fn open_archive_from_bytes(name: &Path, bytes: Vec<u8>) -> io::Result<Box<dyn ArchiveSource>> {
+    let format = archive_format(name)
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "unsupported archive format"))?;
+    build_archive_source(format, Cursor::new(bytes), None)
+}
+
+fn build_archive_source<R: Read + Seek + Send + 'static>(
+    format: ArchiveFormat,
+    reader: R,
+    password: Option<&[u8]>,
+) -> io::Result<Box<dyn ArchiveSource>> {
+    Ok(match format {
+        ArchiveFormat::Zip => Box::new(ZipSource::new(reader, password)?),
+        ArchiveFormat::Tar => Box::new(TarSource::new(reader)),
+        ArchiveFormat::TarGz => Box::new(TarSource::new(GzDecoder::new(reader))),
+        ArchiveFormat::TarXz => Box::new(TarSource::new(XzDecoder::new(reader))),
+        ArchiveFormat::TarZst => Box::new(TarSource::new(ZstdDecoder::new(reader)?)),
+        ArchiveFormat::TarBz2 => Box::new(TarSource::new(BzDecoder::new(reader))),
+    })
+}
+
+/// [`ArchiveSource`] backed by a zip central directory.
+struct ZipSource<R: Read + Seek> {
+    archive: ZipArchive<R>,
+    password: Option<Vec<u8>>,
+}
+
+impl<R: Read + Seek> ZipSource<R> {
+    fn new(reader: R, password: Option<&[u8]>) -> io::Result<Self> {
+        let archive = ZipArchive::new(reader).map_err(to_io_error)?;
+        Ok(Self {
+            archive,
+            password: password.map(|p| p.to_vec()),
+        })
+    }
+
+    /// Reads entry `i`, transparently decrypting it if a password was supplied.
+    ///
+    /// Returns `Ok(None)` for entries that are encrypted but can't be read (no password
+    /// supplied, or the supplied password is wrong) after logging a `warn!`, rather than
+    /// panicking.
+    fn read_entry(&mut self, i: usize) -> io::Result<Option<ZipFile<R>>> {
+        let encrypted = self.archive.by_index_raw(i).map_err(to_io_error)?.encrypted();
+        if !encrypted {
+            return Ok(Some(self.archive.by_index(i).map_err(to_io_error)?));
+        }
+        let password = match &self.password {
+            Some(password) => password,
+            None => {
+                warn!("entry at index {} is encrypted but no password was supplied", i);
+                return Ok(None);
+            }
+        };
+        match self.archive.by_index_decrypt(i, password) {
+            Ok(Ok(file)) => Ok(Some(file)),
+            Ok(Err(_)) => {
+                warn!("incorrect password for encrypted entry at index {}", i);
+                Ok(None)
+            }
+            Err(err) => Err(to_io_error(err)),
+        }
+    }
+}
+
+impl<R: Read + Seek> ArchiveSource for ZipSource<R> {
+    fn for_each_entry(&mut self, visit: &mut dyn FnMut(ArchiveEntry)) -> io::Result<()> {
+        for i in 0..self.archive.len() {
+            let mut file = match self.read_entry(i)? {
+                Some(file) => file,
+                None => continue,
+            };
+            let path = match file.enclosed_name() {
+                Some(path) => path.to_path_buf(),
+                None => continue,
+            };
+            if !supported_compression_method(&file) {
+                warn!("{} is not compressed using a supported method", path.display());
+                continue;
+            }
+            visit(ArchiveEntry {
+                path,
+                reader: Box::new(&mut file),
+            });
+        }
+        Ok(())
+    }
+}
+
+/// [`ArchiveSource`] backed by a (possibly decompressed) tar stream.
+struct TarSource<R: Read> {
+    archive: TarArchive<R>,
+}
+
+impl<R: Read> TarSource<R> {
+    fn new(reader: R) -> Self {
+        Self {
+            archive: TarArchive::new(reader),
+        }
+    }
+}
+
+impl<R: Read> ArchiveSource for TarSource<R> {
+    fn for_each_entry(&mut self, visit: &mut dyn FnMut(ArchiveEntry)) -> io::Result<()> {
+        for entry in self.archive.entries()? {
+            let mut entry = entry?;
+            if !entry.header().entry_type().is_file() {
+                continue;
+            }
+            let path = entry.path()?.into_owned();
+            visit(ArchiveEntry {
+                path,
+                reader: Box::new(&mut entry),
+            });
+        }
+        Ok(())
+    }
+}
+
+fn to_io_error(err: zip::result::ZipError) -> io::Error {
+    io::Error::new(io::ErrorKind::Other, err)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+
+    #[test]
+    fn archive_stem_strips_compound_extensions() {
+        assert_eq!(
+            archive_stem(Path::new("submission.tar.gz")),
+            Some("submission".to_string())
+        );
+        assert_eq!(
+            archive_stem(Path::new("submission.tgz")),
+            Some("submission".to_string())
+        );
+        assert_eq!(
+            archive_stem(Path::new("submission.tar.xz")),
+            Some("submission".to_string())
+        );
+        assert_eq!(
+            archive_stem(Path::new("submission.tar.zst")),
+            Some("submission".to_string())
+        );
+        assert_eq!(
+            archive_stem(Path::new("submission.tar.bz2")),
+            Some("submission".to_string())
+        );
+        assert_eq!(
+            archive_stem(Path::new("submission.tar")),
+            Some("submission".to_string())
+        );
+        assert_eq!(
+            archive_stem(Path::new("submission.zip")),
+            Some("submission".to_string())
+        );
+    }
+
+    #[test]
+    fn archive_stem_rejects_unsupported_extension() {
+        assert_eq!(archive_stem(Path::new("submission.rar")), None);
+    }
+
+    fn encrypted_zip(password: &str) -> Vec<u8> {
+        let mut writer = zip::ZipWriter::new(Cursor::new(Vec::new()));
+        let options = zip::write::FileOptions::default()
+            .compression_method(zip::CompressionMethod::Stored)
+            .with_aes_encryption(zip::AesMode::Aes256, password);
+        writer.start_file("secret.txt", options).unwrap();
+        writer.write_all(b"top secret").unwrap();
+        writer.finish().unwrap().into_inner()
+    }
+
+    #[test]
+    fn read_entry_decrypts_with_correct_password() {
+        let bytes = encrypted_zip("hunter2");
+        let archive = ZipArchive::new(Cursor::new(bytes)).unwrap();
+        let mut source = ZipSource {
+            archive,
+            password: Some(b"hunter2".to_vec()),
+        };
+        let mut file = source.read_entry(0).unwrap().expect("entry should decrypt");
+        let mut contents = Vec::new();
+        file.read_to_end(&mut contents).unwrap();
+        assert_eq!(contents, b"top secret");
+    }
+
+    #[test]
+    fn read_entry_warns_and_returns_none_on_wrong_password() {
+        let bytes = encrypted_zip("hunter2");
+        let archive = ZipArchive::new(Cursor::new(bytes)).unwrap();
+        let mut source = ZipSource {
+            archive,
+            password: Some(b"wrong-password".to_vec()),
+        };
+        assert!(source.read_entry(0).unwrap().is_none());
+    }
+
+    #[test]
+    fn read_entry_warns_and_returns_none_without_password() {
+        let bytes = encrypted_zip("hunter2");
+        let archive = ZipArchive::new(Cursor::new(bytes)).unwrap();
+        let mut source = ZipSource {
+            archive,
+            password: None,
+        };
+        assert!(source.read_entry(0).unwrap().is_none());
+    }
+
+    #[test]
+    fn open_archive_from_bytes_reads_an_in_memory_zip() {
+        let mut writer = zip::ZipWriter::new(Cursor::new(Vec::new()));
+        writer
+            .start_file("nested.txt", zip::write::FileOptions::default())
+            .unwrap();
+        writer.write_all(b"nested contents").unwrap();
+        let bytes = writer.finish().unwrap().into_inner();
+
+        let mut source = open_archive_from_bytes(Path::new("nested.zip"), bytes).unwrap();
+        let mut seen = Vec::new();
+        source
+            .for_each_entry(&mut |entry| seen.push(entry.path))
+            .unwrap();
+        assert_eq!(seen, vec![PathBuf::from("nested.txt")]);
+    }
+
+    #[test]
+    fn open_archive_from_bytes_rejects_unsupported_extension() {
+        assert!(open_archive_from_bytes(Path::new("nested.rar"), Vec::new()).is_err());
+    }
+
+    #[test]
+    fn is_archive_name_recognizes_every_supported_format() {
+        for name in [
+            "a.zip",
+            "a.tar",
+            "a.tar.gz",
+            "a.tgz",
+            "a.tar.xz",
+            "a.tar.zst",
+            "a.tar.bz2",
+        ] {
+            assert!(is_archive_name(Path::new(name)), "{} should be an archive", name);
+        }
+        assert!(!is_archive_name(Path::new("a.rar")));
+        assert!(!is_archive_name(Path::new("a.txt")));
+    }
+}
@@ -0,0 +1,105 @@
+//! Content-hash based deduplication of extracted files.
+//!
+//! Byte-identical submissions (boilerplate, shared starter code) pollute a MOSS similarity
+//! report, so [`DedupIndex`] tracks which content hashes have already been written and
+//! [`hash_while_copying`] computes a file's hash as it's streamed to disk, avoiding a second
+//! read pass.
+use blake2::{Blake2b512, Digest};
+use std::collections::HashMap;
+use std::io::{self, Read, Write};
+
+/// Maps content hashes to the name of the first extracted file with that content.
+#[derive(Debug, Default)]
+pub struct DedupIndex {
+    seen: HashMap<Vec<u8>, String>,
+}
+
+impl DedupIndex {
+    /// Records `output_name` as having content hash `hash`.
+    ///
+    /// Returns the name of the file already recorded under `hash`, if any; in that case the
+    /// index is left unchanged, so the first-seen name remains canonical.
+    pub fn record(&mut self, hash: Vec<u8>, output_name: &str) -> Option<String> {
+        match self.seen.get(&hash) {
+            Some(existing) => Some(existing.clone()),
+            None => {
+                self.seen.insert(hash, output_name.to_string());
+                None
+            }
+        }
+    }
+}
+
+/// Copies `reader` into `writer`, returning the BLAKE2b digest of the bytes copied.
+///
+/// The digest is computed as part of the existing copy rather than in a second pass over the
+/// data.
+pub fn hash_while_copying<R: Read, W: Write>(reader: &mut R, writer: &mut W) -> io::Result<Vec<u8>> {
+    let mut hasher = Blake2b512::new();
+    let mut hashing_reader = HashingReader {
+        inner: reader,
+        hasher: &mut hasher,
+    };
+    io::copy(&mut hashing_reader, writer)?;
+    Ok(hasher.finalize().to_vec())
+}
+
+/// Wraps a [`Read`] so every byte read also feeds a [`Blake2b512`] hasher.
+struct HashingReader<'a, R> {
+    inner: &'a mut R,
+    hasher: &'a mut Blake2b512,
+}
+
+impl<'a, R: Read> Read for HashingReader<'a, R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.inner.read(buf)?;
+        self.hasher.update(&buf[..n]);
+        Ok(n)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    #[test]
+    fn record_returns_first_name_on_collision() {
+        let mut index = DedupIndex::default();
+        assert_eq!(index.record(vec![1, 2, 3], "first.txt"), None);
+        assert_eq!(
+            index.record(vec![1, 2, 3], "second.txt"),
+            Some("first.txt".to_string())
+        );
+    }
+
+    #[test]
+    fn record_does_not_collide_on_different_hashes() {
+        let mut index = DedupIndex::default();
+        assert_eq!(index.record(vec![1, 2, 3], "first.txt"), None);
+        assert_eq!(index.record(vec![4, 5, 6], "second.txt"), None);
+    }
+
+    #[test]
+    fn hash_while_copying_is_equal_for_equal_content() {
+        let mut reader_a = Cursor::new(b"hello world".to_vec());
+        let mut reader_b = Cursor::new(b"hello world".to_vec());
+        let mut out_a = Vec::new();
+        let mut out_b = Vec::new();
+        let hash_a = hash_while_copying(&mut reader_a, &mut out_a).unwrap();
+        let hash_b = hash_while_copying(&mut reader_b, &mut out_b).unwrap();
+        assert_eq!(hash_a, hash_b);
+        assert_eq!(out_a, out_b);
+    }
+
+    #[test]
+    fn hash_while_copying_differs_for_different_content() {
+        let mut reader_a = Cursor::new(b"hello world".to_vec());
+        let mut reader_b = Cursor::new(b"goodbye world".to_vec());
+        let mut out_a = Vec::new();
+        let mut out_b = Vec::new();
+        let hash_a = hash_while_copying(&mut reader_a, &mut out_a).unwrap();
+        let hash_b = hash_while_copying(&mut reader_b, &mut out_b).unwrap();
+        assert_ne!(hash_a, hash_b);
+    }
+}
@@ -2,42 +2,38 @@
 //!
 //! Used mainly for MOSS preprocessing, but can be extended to any arbitrary task. Allows
 //! exclusion of folders in zips, creates no artifacts, runs quickly, easy to understand.
+mod archive;
+mod dedup;
 mod path_verifier;
 
 #[macro_use]
 extern crate log;
 extern crate clap;
+extern crate rayon;
 extern crate simplelog;
+use crate::archive::{
+    archive_stem, is_archive_name, open_archive, open_archive_from_bytes, path_is_archive,
+    ArchiveSource,
+};
+use crate::dedup::DedupIndex;
 use crate::path_verifier::PathVerifier;
 use clap::{App, Arg};
+use rayon::prelude::*;
+use rayon::ThreadPoolBuilder;
 use simplelog::*;
-use std::ffi::OsStr;
+use std::collections::HashMap;
 use std::fs;
 use std::fs::{DirEntry, File};
+use std::io;
+use std::io::Read;
 use std::path::Path;
+use std::sync::Mutex;
 use zip::read::ZipFile;
-use zip::{CompressionMethod, ZipArchive};
+use zip::CompressionMethod;
 
-/// Verifies that a [`DirEntry`] is a zip.
-///
-/// Checks if an entry is a zip by first verifying the entry is a file and ends in ".zip". Files
-/// that have the ".zip" extension but are not able to be handled by the application will
-/// be found in [`extract_files`] via [`supported_compression_method`].
-///
-/// # Examples
-///
-/// ```
-/// use std::fs;
-/// use std::ffi::OsStr;
-///
-/// for entry in fs::read_dir(".") {
-///   println!("{:?} is zip? {}", entry.unwrap().path(), path_is_zip(entry));
-/// }
-/// ```
-fn path_is_zip(entry: &DirEntry) -> bool {
-    let path = entry.path();
-    path.is_file() && path.extension().unwrap_or(OsStr::new("")).eq("zip")
-}
+/// Caps how many bytes of a nested archive entry are buffered into memory before recursing into
+/// it, so a maliciously crafted zip bomb can't be amplified through repeated nesting.
+const MAX_NESTED_ARCHIVE_BYTES: u64 = 200 * 1024 * 1024;
 
 /// Checks if a [`ZipFile`]'s [compression method] is supported.
 ///
@@ -54,78 +50,266 @@ fn supported_compression_method(file: &ZipFile) -> bool {
     }
 }
 
-/// Collects the name and zip archive of all zips in `dir_name`.
+/// Looks up the password to use for the archive named `archive_name`.
+///
+/// An entry in `passwords` (from `--password-file`) takes precedence over `default_password`
+/// (from `--password`).
+fn password_for<'a>(
+    archive_name: &str,
+    passwords: &'a HashMap<String, String>,
+    default_password: Option<&'a str>,
+) -> Option<&'a str> {
+    passwords
+        .get(archive_name)
+        .map(|password| password.as_str())
+        .or(default_password)
+}
+
+/// Collects the name and opened [`ArchiveSource`] of every supported archive in `dir_name`.
 ///
-/// All zips in `dir_name` are returned as a tuple, with the first item representing the file
-/// name of the zip without the extension, and the second item being the [`ZipArchive`]
-fn collect_zips_from_dir(dir_name: &str) -> Vec<(String, ZipArchive<File>)> {
+/// All archives in `dir_name` are returned as a tuple, with the first item representing the file
+/// name of the archive without its (possibly compound, e.g. `.tar.gz`) extension, and the second
+/// item being the opened [`ArchiveSource`]. `passwords` and `default_password` are used to
+/// decrypt encrypted zip entries; see [`password_for`]. An archive that fails to open is
+/// reported with a `warn!` and left out of the result.
+fn collect_archives_from_dir(
+    dir_name: &str,
+    passwords: &HashMap<String, String>,
+    default_password: Option<&str>,
+) -> Vec<(String, Box<dyn ArchiveSource>)> {
     let paths = fs::read_dir(dir_name).unwrap();
-    let mut zips = Vec::new();
+    let mut archives = Vec::new();
     for path in paths {
-        let entry = path.unwrap();
-        if path_is_zip(&entry) {
-            let file_name = entry
-                .file_name()
-                .to_str()
-                .unwrap()
-                .trim_end_matches(".zip")
-                .to_string();
-            let file = File::open(entry.path()).unwrap();
-            let zip = zip::ZipArchive::new(file).unwrap();
-            zips.push((file_name, zip));
+        let entry: DirEntry = path.unwrap();
+        if path_is_archive(&entry.path()) {
+            let file_name = archive_stem(&entry.path()).unwrap();
+            let password = password_for(&file_name, passwords, default_password);
+            match open_archive(&entry.path(), password.map(|p| p.as_bytes())) {
+                Ok(archive) => archives.push((file_name, archive)),
+                Err(err) => warn!("failed to open {} as an archive: {}", file_name, err),
+            }
         } else {
-            warn!("{} is not a zip file", entry.file_name().to_str().unwrap());
+            warn!(
+                "{} is not a supported archive",
+                entry.file_name().to_str().unwrap()
+            );
         }
     }
-    zips.sort_by(|(a, _), (b, _)| a.partial_cmp(b).unwrap());
-    return zips;
+    archives.sort_by(|(a, _), (b, _)| a.partial_cmp(b).unwrap());
+    return archives;
 }
 
-/// Extracts all valid files from `dir_name` and places a copy in `output_dir`.
+/// Shared, immutable state needed while walking an archive, factored out of [`extract_files`] so
+/// [`walk_archive`] can recurse into nested archives without an unwieldy parameter list.
+struct ExtractContext<'a> {
+    base_output_path: &'a Path,
+    dedup_index: &'a Mutex<DedupIndex>,
+    dedup: bool,
+    recursive: bool,
+    max_depth: usize,
+}
+
+/// Walks `archive`, copying matching entries into `ctx.base_output_path`.
 ///
-/// Iterates through all [`ZipFile`]s in a [`ZipArchive`]. If the file is determined to be valid via
-/// the [`PathVerifier`] and the compression method is supported via [`supported_compression_method`],
-/// then we name the new file after the search file name and the original [`ZipArchive`] it began in.
-/// The new file is then copied into `output_dir`.
-fn extract_files(dir_name: &str, verifier: &mut PathVerifier, output_dir: &str) {
-    let zip_archives = collect_zips_from_dir(dir_name);
-    let base_output_path = Path::new(output_dir);
-    for (zip_name, mut zip_archive) in zip_archives {
-        for i in 0..zip_archive.len() {
-            let mut search_file = zip_archive.by_index(i).unwrap();
-            if !verifier.verify(&search_file.enclosed_name().unwrap()) {
-                continue;
+/// `name_chain` holds the archive name and the chain of enclosing archive names (for recursive
+/// extraction), used as the output file's `-`-joined prefix. When `ctx.recursive` is set and
+/// `depth` is under `ctx.max_depth`, entries that are themselves supported archives are buffered
+/// into memory (capped at [`MAX_NESTED_ARCHIVE_BYTES`] to bound zip-bomb amplification) and
+/// recursed into instead of being skipped.
+fn walk_archive(
+    archive: &mut dyn ArchiveSource,
+    name_chain: &mut Vec<String>,
+    depth: usize,
+    verifier: &mut PathVerifier,
+    ctx: &ExtractContext,
+) {
+    archive
+        .for_each_entry(&mut |entry| {
+            let entry_file_name = entry.path.file_name().unwrap().to_str().unwrap().to_string();
+            if ctx.recursive && depth < ctx.max_depth && is_archive_name(&entry.path) {
+                let mut reader = entry.reader;
+                let mut buf = Vec::new();
+                let mut limited = reader.by_ref().take(MAX_NESTED_ARCHIVE_BYTES + 1);
+                io::copy(&mut limited, &mut buf).unwrap();
+                if buf.len() as u64 > MAX_NESTED_ARCHIVE_BYTES {
+                    warn!(
+                        "{} exceeds the nested archive size limit, skipping",
+                        entry_file_name
+                    );
+                    return;
+                }
+                match open_archive_from_bytes(&entry.path, buf) {
+                    Ok(mut nested) => {
+                        name_chain.push(archive_stem(&entry.path).unwrap_or(entry_file_name));
+                        walk_archive(nested.as_mut(), name_chain, depth + 1, verifier, ctx);
+                        name_chain.pop();
+                    }
+                    Err(err) => {
+                        warn!("failed to open {} as a nested archive: {}", entry_file_name, err);
+                    }
+                }
+                return;
             }
-            let search_file_name = search_file
-                .enclosed_name()
-                .unwrap()
-                .file_name()
-                .unwrap()
-                .to_str()
-                .unwrap();
-            if !supported_compression_method(&search_file) {
-                warn!(
-                    "{} is not compressed using a supported method",
-                    search_file_name
-                );
-                continue;
+            if !verifier.verify(&entry.path) {
+                return;
             }
+            let output_file_name = format!("{}-{}", name_chain.join("-"), entry_file_name);
+            let output_file_path = ctx.base_output_path.join(output_file_name.clone());
+            let mut reader = entry.reader;
+            // Output file names can't collide across workers (each is prefixed with its own
+            // archive name chain), and `dedup_index` guards its own state, so the actual
+            // decompression and write below need no lock of their own; `info!`/`warn!` rely on
+            // the logger's internal synchronization to stay coherent.
             info!(
-                "Found matching file {} in {}.zip",
-                search_file_name, zip_name
+                "Found matching file {} in {}",
+                entry_file_name,
+                name_chain.join("-")
             );
-            let output_file_name = format!("{}-{}", zip_name, search_file_name);
-            let output_file_path = base_output_path.join(output_file_name.clone());
-            let mut output_file = File::create(output_file_path).unwrap();
-            info!("Copying file {} to {}", search_file_name, output_file_name);
-            std::io::copy(&mut search_file, &mut output_file).unwrap();
+            let mut output_file = File::create(&output_file_path).unwrap();
+            info!("Copying file {} to {}", entry_file_name, output_file_name);
+            let hash = dedup::hash_while_copying(&mut reader, &mut output_file).unwrap();
+            drop(output_file);
+            if let Some(original) = ctx.dedup_index.lock().unwrap().record(hash, &output_file_name) {
+                warn!("{} is a content-duplicate of {}", output_file_name, original);
+                if ctx.dedup {
+                    fs::remove_file(&output_file_path).unwrap();
+                }
+            }
             info!("Successfully copied file to {}\n", output_file_name);
+        })
+        .unwrap();
+}
+
+/// Extracts all valid files from `dir_name` and places a copy in `output_dir`.
+///
+/// Walks every [`ArchiveSource`] found in `dir_name`, distributing whole archives across a
+/// worker pool of `jobs` threads. Each worker gets its own clone of `verifier` so the
+/// found/not-found bookkeeping stays per-archive. File writes and `info!`/`warn!` logging are
+/// not serialized across workers: each worker writes its own output files, and log lines from
+/// concurrent workers may interleave. The per-archive "not found" reports are aggregated and
+/// printed in archive order once every worker has finished.
+///
+/// If the entry is determined to be valid via the [`PathVerifier`], then we name the new file
+/// after the search file name and the chain of archive names (outer-inner-...) it came from. The
+/// new file is then copied into `output_dir`. See [`walk_archive`] for recursive extraction of
+/// nested archives.
+///
+/// Each copy is hashed as it's written; if its content matches a file extracted earlier, a
+/// `warn!` names the original, and the duplicate's output file is removed when `dedup` is set.
+fn extract_files(
+    dir_name: &str,
+    verifier: &PathVerifier,
+    output_dir: &str,
+    jobs: usize,
+    passwords: &HashMap<String, String>,
+    default_password: Option<&str>,
+    dedup: bool,
+    recursive: bool,
+    max_depth: usize,
+) {
+    let archives = collect_archives_from_dir(dir_name, passwords, default_password);
+    let base_output_path = Path::new(output_dir);
+    let ctx = ExtractContext {
+        base_output_path,
+        dedup_index: &Mutex::new(DedupIndex::default()),
+        dedup,
+        recursive,
+        max_depth,
+    };
+    let pool = ThreadPoolBuilder::new()
+        .num_threads(jobs)
+        .build()
+        .expect("failed to build worker pool");
+    let reports: Vec<Vec<String>> = pool.install(|| {
+        archives
+            .into_par_iter()
+            .map(|(archive_name, mut archive)| {
+                let mut archive_verifier = verifier.clone();
+                let mut name_chain = vec![archive_name.clone()];
+                walk_archive(archive.as_mut(), &mut name_chain, 0, &mut archive_verifier, &ctx);
+                archive_verifier.unmatched_report(&archive_name)
+            })
+            .collect()
+    });
+    for report in reports {
+        for message in report {
+            error!("{}", message);
         }
-        verifier.print_progress(&zip_name);
-        verifier.reset();
     }
 }
 
+/// Extracts matching entries from a single zip archive piped in over stdin.
+///
+/// Triggered by `--stdin` or `--dir -`. A pipe can't be seeked back to read a central
+/// directory, so entries are read sequentially via [`zip::read::read_zipfile_from_stream`]
+/// instead of [`zip::ZipArchive`]; compression-method and validity checks happen per-entry as
+/// the stream is consumed rather than up front. Matching entries are copied to `output_dir`
+/// under a synthesized `stdin-{file_name}` name.
+fn extract_stdin(verifier: &mut PathVerifier, output_dir: &str, dedup: bool) {
+    let base_output_path = Path::new(output_dir);
+    let mut dedup_index = DedupIndex::default();
+    let stdin = io::stdin();
+    let mut reader = stdin.lock();
+    loop {
+        let mut file = match zip::read::read_zipfile_from_stream(&mut reader) {
+            Ok(Some(file)) => file,
+            Ok(None) => break,
+            Err(err) => {
+                warn!("Failed to read next entry from stdin: {}", err);
+                break;
+            }
+        };
+        let path = match file.enclosed_name() {
+            Some(path) => path.to_path_buf(),
+            None => continue,
+        };
+        if !verifier.verify(&path) {
+            continue;
+        }
+        let search_file_name = path.file_name().unwrap().to_str().unwrap();
+        if !supported_compression_method(&file) {
+            warn!(
+                "{} is not compressed using a supported method",
+                search_file_name
+            );
+            continue;
+        }
+        info!("Found matching file {} on stdin", search_file_name);
+        let output_file_name = format!("stdin-{}", search_file_name);
+        let output_file_path = base_output_path.join(output_file_name.clone());
+        let mut output_file = File::create(&output_file_path).unwrap();
+        info!("Copying file {} to {}", search_file_name, output_file_name);
+        let hash = dedup::hash_while_copying(&mut file, &mut output_file).unwrap();
+        drop(output_file);
+        if let Some(original) = dedup_index.record(hash, &output_file_name) {
+            warn!(
+                "{} is a content-duplicate of {}",
+                output_file_name, original
+            );
+            if dedup {
+                fs::remove_file(&output_file_path).unwrap();
+            }
+        }
+        info!("Successfully copied file to {}\n", output_file_name);
+    }
+    verifier.print_progress(&"stdin".to_string());
+}
+
+/// Parses a `--password-file` mapping archive names (without extension) to passwords.
+///
+/// Expects one `archive_name=password` pair per line; blank lines and lines starting with `#`
+/// are ignored.
+fn parse_password_file(path: &str) -> HashMap<String, String> {
+    let contents = fs::read_to_string(path).unwrap();
+    contents
+        .lines()
+        .map(|line| line.trim())
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .filter_map(|line| line.split_once('='))
+        .map(|(name, password)| (name.trim().to_string(), password.trim().to_string()))
+        .collect()
+}
+
 /// Prints beginning information when in verbose use.
 fn print_info(dir_name: &str, search_files: &Vec<&str>) {
     let n = search_files.len();
@@ -143,6 +327,18 @@ fn print_info(dir_name: &str, search_files: &Vec<&str>) {
     }
 }
 
+/// Checks `output_dir` exists and is a directory.
+fn check_output_dir(output_dir: &str) -> Result<(), &'static str> {
+    let output_dir = File::open(output_dir);
+    if output_dir.is_err() {
+        return Err("Output directory doesn't exist.");
+    }
+    if output_dir.expect("").metadata().unwrap().is_file() {
+        return Err("Output directory cannot be a file.");
+    }
+    return Ok(());
+}
+
 /// Checks `dir_name` and `output_dir` both exist and are directories.
 fn check_dirs(dir_name: &str, output_dir: &str) -> Result<(), &'static str> {
     let input_dir = File::open(dir_name);
@@ -152,14 +348,7 @@ fn check_dirs(dir_name: &str, output_dir: &str) -> Result<(), &'static str> {
     if input_dir.expect("").metadata().unwrap().is_file() {
         return Err("Input directory cannot be a file.");
     }
-    let output_dir = File::open(output_dir);
-    if output_dir.is_err() {
-        return Err("Output directory doesn't exist.");
-    }
-    if output_dir.expect("").metadata().unwrap().is_file() {
-        return Err("Output directory cannot be a file.");
-    }
-    return Ok(());
+    check_output_dir(output_dir)
 }
 
 fn main() {
@@ -171,10 +360,15 @@ fn main() {
                 .short("d")
                 .long("dir")
                 .value_name("dir")
-                .help("The directory with all submissions (as zip files)")
-                .required(true)
+                .help("The directory with all submissions (as zip or tar archives), or \"-\" to read a single zip from stdin")
+                .required_unless("stdin")
                 .takes_value(true),
         )
+        .arg(
+            Arg::with_name("stdin")
+                .long("stdin")
+                .help("Read a single zip archive piped in over stdin instead of a directory"),
+        )
         .arg(
             Arg::with_name("output")
                 .short("o")
@@ -215,6 +409,49 @@ fn main() {
                 .default_value("moss-fmt.log")
                 .help("Outputs information to a file instead of terminal"),
         )
+        .arg(
+            Arg::with_name("jobs")
+                .short("j")
+                .long("jobs")
+                .value_name("jobs")
+                .takes_value(true)
+                .default_value("1")
+                .help("Number of archives to extract in parallel"),
+        )
+        .arg(
+            Arg::with_name("password")
+                .short("p")
+                .long("password")
+                .value_name("password")
+                .takes_value(true)
+                .help("Password to decrypt encrypted zip entries"),
+        )
+        .arg(
+            Arg::with_name("password-file")
+                .long("password-file")
+                .value_name("password-file")
+                .takes_value(true)
+                .help("File mapping archive names to passwords, one `name=password` pair per line"),
+        )
+        .arg(
+            Arg::with_name("dedup")
+                .long("dedup")
+                .help("Remove extracted files whose content duplicates a previously extracted file"),
+        )
+        .arg(
+            Arg::with_name("recursive")
+                .short("r")
+                .long("recursive")
+                .help("Recurse into archives nested inside a submission archive"),
+        )
+        .arg(
+            Arg::with_name("max-depth")
+                .long("max-depth")
+                .value_name("max-depth")
+                .takes_value(true)
+                .default_value("5")
+                .help("Maximum nesting depth for --recursive"),
+        )
         .get_matches();
 
     let mut verifier = PathVerifier::default();
@@ -226,9 +463,27 @@ fn main() {
             verifier = verifier.add_restricted_folder(dir_name);
         }
     }
-    let dir_name = matches.value_of("dir").unwrap();
+    let dir_name = matches.value_of("dir");
+    let use_stdin = matches.is_present("stdin") || dir_name == Some("-");
     let output_dir = matches.value_of("output").unwrap_or("./");
     let verbose = matches.is_present("verbosity");
+    let jobs: usize = matches
+        .value_of("jobs")
+        .unwrap()
+        .parse()
+        .expect("jobs must be a positive integer");
+    let default_password = matches.value_of("password");
+    let passwords = matches
+        .value_of("password-file")
+        .map(parse_password_file)
+        .unwrap_or_default();
+    let dedup = matches.is_present("dedup");
+    let recursive = matches.is_present("recursive");
+    let max_depth: usize = matches
+        .value_of("max-depth")
+        .unwrap()
+        .parse()
+        .expect("max-depth must be a positive integer");
     CombinedLogger::init(vec![
         TermLogger::new(
             if verbose {
@@ -247,16 +502,85 @@ fn main() {
         ),
     ])
     .unwrap();
-    match check_dirs(dir_name, output_dir) {
+    let check_result = if use_stdin {
+        check_output_dir(output_dir)
+    } else {
+        check_dirs(dir_name.unwrap(), output_dir)
+    };
+    match check_result {
         Ok(_) => (),
         Err(msg) => {
             error!("{}", msg);
             return;
         }
     }
+    if use_stdin {
+        extract_stdin(&mut verifier, output_dir, dedup);
+        return;
+    }
+    let dir_name = dir_name.unwrap();
     if verbose {
         let file_names: Vec<&str> = matches.values_of("file").unwrap().collect::<Vec<&str>>();
         print_info(dir_name, &file_names);
     }
-    extract_files(dir_name, &mut verifier, output_dir);
+    extract_files(
+        dir_name,
+        &verifier,
+        output_dir,
+        jobs,
+        &passwords,
+        default_password,
+        dedup,
+        recursive,
+        max_depth,
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+
+    #[test]
+    fn password_for_prefers_archive_specific_password() {
+        let mut passwords = HashMap::new();
+        passwords.insert("submission1".to_string(), "specific".to_string());
+        assert_eq!(
+            password_for("submission1", &passwords, Some("default")),
+            Some("specific")
+        );
+    }
+
+    #[test]
+    fn password_for_falls_back_to_default() {
+        let passwords = HashMap::new();
+        assert_eq!(
+            password_for("submission1", &passwords, Some("default")),
+            Some("default")
+        );
+    }
+
+    #[test]
+    fn password_for_returns_none_without_a_match_or_default() {
+        let passwords = HashMap::new();
+        assert_eq!(password_for("submission1", &passwords, None), None);
+    }
+
+    #[test]
+    fn parse_password_file_skips_blank_lines_and_comments() {
+        let path = std::env::temp_dir().join("moss-fmt-test-password-file.txt");
+        let mut file = File::create(&path).unwrap();
+        writeln!(file, "# a comment").unwrap();
+        writeln!(file).unwrap();
+        writeln!(file, "submission1 = hunter2").unwrap();
+        writeln!(file, "submission2=swordfish").unwrap();
+        drop(file);
+
+        let passwords = parse_password_file(path.to_str().unwrap());
+        fs::remove_file(&path).unwrap();
+
+        assert_eq!(passwords.get("submission1"), Some(&"hunter2".to_string()));
+        assert_eq!(passwords.get("submission2"), Some(&"swordfish".to_string()));
+        assert_eq!(passwords.len(), 2);
+    }
 }
@@ -1,28 +1,42 @@
-use std::collections::{HashMap, HashSet};
+use glob::Pattern;
 use std::path::Path;
 
+/// A search file pattern along with whether it has matched anything since the last [`reset`].
+///
+/// [`reset`]: PathVerifier::reset
+#[derive(Debug, Clone)]
+struct SearchPattern {
+    pattern: Pattern,
+    found: bool,
+}
+
 /// Struct that verifies [`Path`] objects against provided arguments
 ///
-/// Holds a [`HashSet`] of folders to exclude and a [`HashMap`] of files the user is searching for.
-#[derive(Debug)]
+/// Holds a [`Vec`] of glob [`Pattern`]s to exclude and a [`Vec`] of [`SearchPattern`]s the user
+/// is searching for.
+#[derive(Debug, Clone)]
 pub struct PathVerifier {
-    restricted_folders: HashSet<String>,
-    search_files: HashMap<String, bool>,
+    restricted_folders: Vec<Pattern>,
+    search_files: Vec<SearchPattern>,
 }
 
 impl PathVerifier {
-    /// Add a new file name to search for
+    /// Add a new glob pattern to search for, e.g. `*.java` or `src/**/Main.c`
     pub fn add_search_file(&mut self, file_name: &str) -> Self {
-        self.search_files.insert(file_name.to_string(), false);
+        self.search_files.push(SearchPattern {
+            pattern: Pattern::new(file_name).expect("invalid search file pattern"),
+            found: false,
+        });
         Self {
             restricted_folders: self.restricted_folders.clone(),
             search_files: self.search_files.clone(),
         }
     }
 
-    /// Add a new folder to exclude from search
+    /// Add a new glob pattern to exclude from search, matched against individual path components
     pub fn add_restricted_folder(&mut self, folder_name: &str) -> Self {
-        self.restricted_folders.insert(folder_name.to_string());
+        self.restricted_folders
+            .push(Pattern::new(folder_name).expect("invalid restricted folder pattern"));
         Self {
             restricted_folders: self.restricted_folders.clone(),
             search_files: self.search_files.clone(),
@@ -31,8 +45,15 @@ impl PathVerifier {
 
     /// Test if the [`Path`] is valid per user constaints
     ///
-    /// The [`Path`] must not have any folder component which is in the excluded folders and
-    /// the file name must be in the set of names to search for.
+    /// The [`Path`] must not have any path component which matches an excluded pattern, and it
+    /// must match one of the search patterns (against either the full relative path or just the
+    /// file name, so bare patterns like `index.js` still match regardless of nesting). Patterns
+    /// are matched while walking, not pre-expanded, so deeply nested trees stay cheap.
+    ///
+    /// Each search pattern accepts only the first path it matches until the next [`reset`];
+    /// a later path that matches only already-matched patterns is rejected.
+    ///
+    /// [`reset`]: PathVerifier::reset
     ///
     /// # Example
     ///
@@ -45,45 +66,61 @@ impl PathVerifier {
     /// assert!(!verifier.verify(file_path));
     /// ```
     pub fn verify(&mut self, path: &Path) -> bool {
-        let mut pieces = path.components().map(|comp| comp.as_os_str());
-        let file_name = path.file_name().unwrap().to_str().unwrap().to_string();
-        if *self.search_files.get(&file_name).unwrap_or(&true) {
+        let file_name = path.file_name().unwrap().to_str().unwrap();
+        let in_restricted_folder = path.components().any(|comp| {
+            let comp = comp.as_os_str().to_str().unwrap();
+            self.restricted_folders
+                .iter()
+                .any(|pattern| pattern.matches(comp))
+        });
+        if in_restricted_folder {
             return false;
         }
-        let valid = pieces.all(|comp| !self.restricted_folders.contains(comp.to_str().unwrap()))
-            && self.search_files.contains_key(&file_name);
-        self.search_files.insert(file_name, valid);
-        return valid;
+        let path_str = path.to_str().unwrap();
+        for entry in self.search_files.iter_mut() {
+            if entry.found {
+                continue;
+            }
+            if entry.pattern.matches(path_str) || entry.pattern.matches(file_name) {
+                entry.found = true;
+                return true;
+            }
+        }
+        return false;
     }
 
     /// Resets the progress of a PathVerifier
     pub fn reset(&mut self) -> () {
-        for key in self.search_files.clone().keys() {
-            self.search_files.insert(key.to_string(), false);
+        for entry in self.search_files.iter_mut() {
+            entry.found = false;
         }
     }
 
     pub fn print_progress(&self, folder_name: &String) -> () {
-        let not_found = self
-            .search_files
-            .clone()
-            .into_iter()
-            .filter_map(|(k, v)| if v { None } else { Some(k) })
-            .collect::<Vec<String>>();
-        for name in not_found {
-          error!("{} was not found in {}", name, folder_name);
+        for message in self.unmatched_report(folder_name) {
+            error!("{}", message);
         }
     }
+
+    /// Builds the "not found" report for `folder_name` without printing it, so callers that
+    /// process archives concurrently can aggregate reports and print them in order afterward.
+    pub fn unmatched_report(&self, folder_name: &str) -> Vec<String> {
+        self.search_files
+            .iter()
+            .filter(|entry| !entry.found)
+            .map(|entry| format!("{} was not found in {}", entry.pattern.as_str(), folder_name))
+            .collect()
+    }
 }
 
 impl Default for PathVerifier {
     fn default() -> Self {
         Self {
-            restricted_folders: ["__MACOSX".to_owned(), "node_modules".to_owned()]
+            restricted_folders: ["__MACOSX", "node_modules"]
                 .iter()
-                .cloned()
+                .map(|name| Pattern::new(name).unwrap())
                 .collect(),
-            search_files: HashMap::new(),
+            search_files: Vec::new(),
         }
     }
 }
@@ -133,4 +170,27 @@ mod tests {
         let mut verifier = PathVerifier::default().add_search_file("index.js");
         assert!(!verifier.verify(file_path));
     }
+
+    #[test]
+    fn path_verifier_accepts_glob_search_pattern() {
+        let file_path = Path::new("src/Main.java");
+        let mut verifier = PathVerifier::default().add_search_file("*.java");
+        assert!(verifier.verify(file_path));
+    }
+
+    #[test]
+    fn path_verifier_accepts_recursive_glob_search_pattern() {
+        let file_path = Path::new("src/a/b/Main.c");
+        let mut verifier = PathVerifier::default().add_search_file("src/**/Main.c");
+        assert!(verifier.verify(file_path));
+    }
+
+    #[test]
+    fn path_verifier_denies_file_in_glob_restricted_folder() {
+        let file_path = Path::new("build/out/index.js");
+        let mut verifier = PathVerifier::default()
+            .add_search_file("index.js")
+            .add_restricted_folder("build*");
+        assert!(!verifier.verify(file_path));
+    }
 }